@@ -1,12 +1,17 @@
 use std::error;
 use std::fmt;
+use std::marker::PhantomData;
 
+use digest::generic_array::GenericArray;
+use digest::{BlockInput, Digest, FixedOutput, Input, Reset};
 use hmac::{Hmac, Mac};
-use sha2::Sha256;
+use sha2::{Sha256, Sha384, Sha512};
+use zeroize::{Zeroize, Zeroizing};
 
 #[derive(Debug)]
 pub enum HKDFError {
     UnrecognizedMessageVersion(u32),
+    OutputTooLong { requested: usize, max: usize },
 }
 
 impl fmt::Display for HKDFError {
@@ -15,87 +20,288 @@ impl fmt::Display for HKDFError {
             HKDFError::UnrecognizedMessageVersion(message_version) => {
                 write!(f, "unrecognized message version <{}>", message_version)
             }
+            HKDFError::OutputTooLong { requested, max } => write!(
+                f,
+                "requested output of {} bytes exceeds the RFC 5869 maximum of {} bytes",
+                requested, max
+            ),
         }
     }
 }
 
 impl error::Error for HKDFError {}
 
-#[derive(Clone, Copy, Debug)]
-pub struct HKDF {
+/// A hash function HKDF can run HMAC over: SHA-256, SHA-384, SHA-512, etc.
+pub trait HkdfDigest: Input + BlockInput + FixedOutput + Reset + Default + Clone + Digest {}
+
+impl<D: Input + BlockInput + FixedOutput + Reset + Default + Clone + Digest> HkdfDigest for D {}
+
+/// One HMAC block's worth of output for `D`, stack-allocated.
+type Block<D> = GenericArray<u8, <D as FixedOutput>::OutputSize>;
+
+/// HKDF (RFC 5869) parameterized over the underlying hash function `D`.
+///
+/// Use the [`HkdfSha256`], [`HkdfSha384`] or [`HkdfSha512`] aliases to pick a
+/// suite, or go through [`HKDF::new`] for the SHA-256 instance used by the
+/// wire protocol.
+pub struct HKDF<D: HkdfDigest = Sha256> {
     iteration_start_offset: u8,
+    hash: PhantomData<D>,
 }
 
-impl HKDF {
-    const HASH_OUTPUT_SIZE: usize = 32;
+impl<D: HkdfDigest> Clone for HKDF<D> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
 
-    pub fn new(message_version: u32) -> Result<Self, HKDFError> {
+impl<D: HkdfDigest> Copy for HKDF<D> {}
+
+impl<D: HkdfDigest> fmt::Debug for HKDF<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HKDF")
+            .field("iteration_start_offset", &self.iteration_start_offset)
+            .finish()
+    }
+}
+
+pub type HkdfSha256 = HKDF<Sha256>;
+pub type HkdfSha384 = HKDF<Sha384>;
+pub type HkdfSha512 = HKDF<Sha512>;
+
+impl<D: HkdfDigest> HKDF<D> {
+    fn hash_output_size() -> usize {
+        D::output_size()
+    }
+
+    /// Computes the number of HMAC iterations needed to produce
+    /// `output_length` bytes, enforcing RFC 5869's 255-iteration limit (the
+    /// per-block counter is a single byte).
+    fn checked_iterations(self, output_length: usize) -> Result<usize, HKDFError> {
+        let hash_output_size = Self::hash_output_size();
+        let iterations = (output_length + hash_output_size - 1) / hash_output_size;
+        let max_iterations = std::cmp::min(255, 256 - self.iteration_start_offset as usize);
+        if iterations > max_iterations {
+            return Err(HKDFError::OutputTooLong {
+                requested: output_length,
+                max: max_iterations * hash_output_size,
+            });
+        }
+        Ok(iterations)
+    }
+
+    /// Constructs an `HKDF` for the hash `D`, selecting the RFC 5869 message
+    /// version's iteration counter offset.
+    pub fn new_for_hash(message_version: u32) -> Result<Self, HKDFError> {
         match message_version {
             2 => Ok(HKDF {
                 iteration_start_offset: 0,
+                hash: PhantomData,
             }),
             3 => Ok(HKDF {
                 iteration_start_offset: 1,
+                hash: PhantomData,
             }),
             _ => Err(HKDFError::UnrecognizedMessageVersion(message_version)),
         }
     }
 
+    /// See the zeroization note on [`HKDF::expand_multi`]: the returned
+    /// `Box<[u8]>` holds output keying material and is not zeroized on drop.
     pub fn derive_secrets(
         self,
         input_key_material: &[u8],
         info: &[u8],
         output_length: usize,
-    ) -> Box<[u8]> {
+    ) -> Result<Box<[u8]>, HKDFError> {
         self.derive_salted_secrets(
             input_key_material,
-            &[0u8; Self::HASH_OUTPUT_SIZE],
+            &vec![0u8; Self::hash_output_size()],
             info,
             output_length,
         )
     }
 
+    /// See the zeroization note on [`HKDF::expand_multi`]: the returned
+    /// `Box<[u8]>` holds output keying material and is not zeroized on drop.
     pub fn derive_salted_secrets(
         self,
         input_key_material: &[u8],
         salt: &[u8],
         info: &[u8],
         output_length: usize,
-    ) -> Box<[u8]> {
-        let prk = self.extract(salt, input_key_material);
+    ) -> Result<Box<[u8]>, HKDFError> {
+        let prk = self.extract_prk(salt, input_key_material);
         self.expand(&prk, info, output_length)
     }
 
-    fn extract(self, salt: &[u8], input_key_material: &[u8]) -> [u8; Self::HASH_OUTPUT_SIZE] {
+    /// Like [`HKDF::derive_salted_secrets`], but writes `out.len()` bytes of
+    /// output keying material directly into `out` with no heap allocation:
+    /// the PRK is held in a stack-sized buffer and expansion streams
+    /// straight into `out`, same as [`HKDF::expand_into`].
+    pub fn derive_into(
+        self,
+        input_key_material: &[u8],
+        salt: &[u8],
+        info: &[u8],
+        out: &mut [u8],
+    ) -> Result<(), HKDFError> {
+        let mut prk = self.extract_prk_fixed(salt, input_key_material);
+        let result = self.expand_into(&prk, info, out);
+        prk.zeroize();
+        result
+    }
+
+    /// Runs the HKDF-Extract step, returning a pseudorandom key (PRK) that
+    /// can be fed into [`HKDF::expand`] as many times as needed without
+    /// re-running extraction for each output.
+    ///
+    /// The PRK is secret key material, so it's returned in a [`Zeroizing`]
+    /// container that wipes it on drop.
+    pub fn extract_prk(self, salt: &[u8], input_key_material: &[u8]) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(self.extract_prk_fixed(salt, input_key_material).to_vec())
+    }
+
+    /// Same as [`HKDF::extract_prk`], but returns the PRK in a stack-sized
+    /// buffer instead of an allocated `Vec`, for callers on the
+    /// allocation-free path (see [`HKDF::derive_into`]).
+    fn extract_prk_fixed(self, salt: &[u8], input_key_material: &[u8]) -> Block<D> {
         let mut mac =
-            Hmac::<Sha256>::new_varkey(salt).expect("HMAC-SHA256 should accept any size key");
+            Hmac::<D>::new_varkey(salt).expect("HMAC should accept any size key");
         mac.input(input_key_material);
-        mac.result().code().into()
+        mac.result().code()
     }
 
-    fn expand(
+    /// Runs the HKDF-Expand step against a PRK obtained from
+    /// [`HKDF::extract_prk`] (or derived elsewhere), producing
+    /// `output_length` bytes of output keying material.
+    ///
+    /// See [`HKDF::expand_multi`] for the output-length limit and zeroization
+    /// behavior this shares.
+    pub fn expand(
         self,
-        prk: &[u8; Self::HASH_OUTPUT_SIZE],
+        prk: &[u8],
         info: &[u8],
         output_length: usize,
-    ) -> Box<[u8]> {
-        let iterations = (output_length + Self::HASH_OUTPUT_SIZE - 1) / Self::HASH_OUTPUT_SIZE;
-        let mut result = Vec::<u8>::with_capacity(iterations * Self::HASH_OUTPUT_SIZE);
-        let mut mac =
-            Hmac::<Sha256>::new_varkey(prk).expect("HMAC-SHA256 should accept any size key");
+    ) -> Result<Box<[u8]>, HKDFError> {
+        self.expand_multi(prk, &[info], output_length)
+    }
+
+    /// Like [`HKDF::expand`], but feeds `info` as several fragments in
+    /// sequence instead of requiring the caller to concatenate them first.
+    /// Useful when `info` is assembled from labeled components (protocol
+    /// label, context, transcript hash, ...) as in TLS 1.3-style key
+    /// schedules.
+    ///
+    /// RFC 5869 caps the number of HMAC iterations at 255 (the per-block
+    /// counter is a single byte); returns
+    /// [`HKDFError::OutputTooLong`] rather than wrapping the counter if
+    /// `output_length` would exceed that.
+    ///
+    /// The returned `Box<[u8]>` holds output keying material and is *not*
+    /// zeroized on drop; the caller is responsible for wiping it once it's
+    /// no longer needed. Only the rolling per-block buffer used internally
+    /// to chain HMAC iterations is zeroized.
+    pub fn expand_multi(
+        self,
+        prk: &[u8],
+        info: &[&[u8]],
+        output_length: usize,
+    ) -> Result<Box<[u8]>, HKDFError> {
+        let hash_output_size = Self::hash_output_size();
+        let iterations = self.checked_iterations(output_length)?;
+
+        let mut result = Vec::<u8>::with_capacity(output_length);
+        let mut mac = Hmac::<D>::new_varkey(prk).expect("HMAC should accept any size key");
+        let mut previous_block: Option<Block<D>> = None;
 
         for i in 0..iterations {
-            if result.len() >= Self::HASH_OUTPUT_SIZE {
-                mac.input(&result[(result.len() - Self::HASH_OUTPUT_SIZE)..]);
+            if let Some(previous_block) = &previous_block {
+                mac.input(previous_block);
+            }
+            for info_fragment in info {
+                mac.input(info_fragment);
             }
-            mac.input(info);
             mac.input(&[(i as u8) + self.iteration_start_offset]);
-            let d = mac.result_reset().code();
-            result.extend_from_slice(&d[..]);
+            let block = mac.result_reset().code();
+
+            let take = std::cmp::min(hash_output_size, output_length - result.len());
+            result.extend_from_slice(&block[..take]);
+
+            if let Some(mut previous_block) = previous_block.replace(block) {
+                previous_block.zeroize();
+            }
+        }
+        if let Some(mut previous_block) = previous_block {
+            previous_block.zeroize();
+        }
+
+        Ok(result.into_boxed_slice())
+    }
+
+    /// Like [`HKDF::expand`], but writes `out.len()` bytes of output keying
+    /// material directly into `out` instead of allocating a `Box<[u8]>`.
+    pub fn expand_into(
+        self,
+        prk: &[u8],
+        info: &[u8],
+        out: &mut [u8],
+    ) -> Result<(), HKDFError> {
+        self.expand_multi_into(prk, &[info], out)
+    }
+
+    /// Like [`HKDF::expand_multi`], but writes `out.len()` bytes of output
+    /// keying material directly into `out` with no heap allocation: each
+    /// HMAC block is streamed straight into `out`, buffering only the single
+    /// previous block needed to feed the next iteration's MAC. This avoids
+    /// the `Vec`/`Box` traffic `expand_multi` incurs; it does not make this
+    /// module `no_std`-capable on its own, since the rest of the module
+    /// still pulls in `std` (see the top-of-file imports).
+    pub fn expand_multi_into(
+        self,
+        prk: &[u8],
+        info: &[&[u8]],
+        out: &mut [u8],
+    ) -> Result<(), HKDFError> {
+        let hash_output_size = Self::hash_output_size();
+        let output_length = out.len();
+        let iterations = self.checked_iterations(output_length)?;
+
+        let mut mac = Hmac::<D>::new_varkey(prk).expect("HMAC should accept any size key");
+        let mut previous_block: Option<Block<D>> = None;
+        let mut written = 0;
+
+        for i in 0..iterations {
+            if let Some(previous_block) = &previous_block {
+                mac.input(previous_block);
+            }
+            for info_fragment in info {
+                mac.input(info_fragment);
+            }
+            mac.input(&[(i as u8) + self.iteration_start_offset]);
+            let block = mac.result_reset().code();
+
+            let take = std::cmp::min(hash_output_size, output_length - written);
+            out[written..written + take].copy_from_slice(&block[..take]);
+            written += take;
+
+            if let Some(mut previous_block) = previous_block.replace(block) {
+                previous_block.zeroize();
+            }
+        }
+        if let Some(mut previous_block) = previous_block {
+            previous_block.zeroize();
         }
 
-        result.truncate(output_length);
-        result.into_boxed_slice()
+        Ok(())
+    }
+}
+
+impl HKDF<Sha256> {
+    /// SHA-256 is the hash the wire protocol has always used; this keeps
+    /// `HKDF::new(message_version)` as the default entry point.
+    pub fn new(message_version: u32) -> Result<Self, HKDFError> {
+        Self::new_for_hash(message_version)
     }
 }
 
@@ -121,7 +327,8 @@ mod tests {
 
         let output = HKDF::new(3)
             .unwrap()
-            .derive_salted_secrets(&ikm, &salt, &info, okm.len());
+            .derive_salted_secrets(&ikm, &salt, &info, okm.len())
+            .unwrap();
 
         assert_eq!(&okm[..], &output[..]);
     }
@@ -163,7 +370,8 @@ mod tests {
 
         let output = HKDF::new(3)
             .unwrap()
-            .derive_salted_secrets(&ikm, &salt, &info, okm.len());
+            .derive_salted_secrets(&ikm, &salt, &info, okm.len())
+            .unwrap();
 
         assert_eq!(&okm[..], &output[..]);
     }
@@ -188,8 +396,113 @@ mod tests {
 
         let output = HKDF::new(2)
             .unwrap()
-            .derive_salted_secrets(&ikm, &salt, &info, okm.len());
+            .derive_salted_secrets(&ikm, &salt, &info, okm.len())
+            .unwrap();
 
         assert_eq!(&okm[..], &output[..]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_vector_v3_sha384() {
+        // Inputs of RFC 5869 A.1 (basic test case), run with SHA-384
+        let ikm = [0x0bu8; 22];
+        let salt = [
+            0x00u8, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c,
+        ];
+        let info = [0xf0u8, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9];
+        let okm = [
+            0x9bu8, 0x50, 0x97, 0xa8, 0x60, 0x38, 0xb8, 0x05, 0x30, 0x90, 0x76, 0xa4, 0x4b, 0x3a,
+            0x9f, 0x38, 0x06, 0x3e, 0x25, 0xb5, 0x16, 0xdc, 0xbf, 0x36, 0x9f, 0x39, 0x4c, 0xfa,
+            0xb4, 0x36, 0x85, 0xf7, 0x48, 0xb6, 0x45, 0x77, 0x63, 0xe4, 0xf0, 0x20, 0x4f, 0xc5,
+        ];
+
+        let output = HkdfSha384::new_for_hash(3)
+            .unwrap()
+            .derive_salted_secrets(&ikm, &salt, &info, okm.len())
+            .unwrap();
+
+        assert_eq!(&okm[..], &output[..]);
+    }
+
+    #[test]
+    fn test_extract_then_expand_matches_derive_salted_secrets() {
+        let ikm = [0x0bu8; 22];
+        let salt = [
+            0x00u8, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c,
+        ];
+        let info = [0xf0u8, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9];
+
+        let hkdf = HKDF::new(3).unwrap();
+        let prk = hkdf.extract_prk(&salt, &ikm);
+        let expanded_once = hkdf.expand(&prk, &info, 42).unwrap();
+        let expanded_again = hkdf.expand(&prk, &info, 42).unwrap();
+
+        assert_eq!(
+            &expanded_once[..],
+            &hkdf.derive_salted_secrets(&ikm, &salt, &info, 42).unwrap()[..]
+        );
+        assert_eq!(&expanded_once[..], &expanded_again[..]);
+    }
+
+    #[test]
+    fn test_expand_rejects_output_beyond_counter_range() {
+        let hkdf = HKDF::new(3).unwrap();
+        let prk = vec![0u8; 32];
+
+        // v3's iteration_start_offset of 1 leaves 255 usable counter values.
+        let max_output = 255 * 32;
+        assert!(hkdf.expand(&prk, b"info", max_output).is_ok());
+
+        let err = hkdf.expand(&prk, b"info", max_output + 1).unwrap_err();
+        match err {
+            HKDFError::OutputTooLong { requested, max } => {
+                assert_eq!(requested, max_output + 1);
+                assert_eq!(max, max_output);
+            }
+            _ => panic!("expected HKDFError::OutputTooLong"),
+        }
+    }
+
+    #[test]
+    fn test_expand_multi_matches_concatenated_info() {
+        let hkdf = HKDF::new(3).unwrap();
+        let prk = vec![0x0bu8; 32];
+
+        let concatenated = hkdf.expand(&prk, b"protocol-labelcontexttranscript", 64).unwrap();
+        let fragmented = hkdf
+            .expand_multi(&prk, &[b"protocol-label", b"context", b"transcript"], 64)
+            .unwrap();
+
+        assert_eq!(&concatenated[..], &fragmented[..]);
+    }
+
+    #[test]
+    fn test_expand_into_matches_expand() {
+        let hkdf = HKDF::new(3).unwrap();
+        let prk = vec![0x0bu8; 32];
+
+        let boxed = hkdf.expand(&prk, b"info", 70).unwrap();
+        let mut buf = [0u8; 70];
+        hkdf.expand_into(&prk, b"info", &mut buf).unwrap();
+
+        assert_eq!(&boxed[..], &buf[..]);
+    }
+
+    #[test]
+    fn test_derive_into_matches_derive_salted_secrets() {
+        let ikm = [0x0bu8; 22];
+        let salt = [
+            0x00u8, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c,
+        ];
+        let info = [0xf0u8, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9];
+
+        let hkdf = HKDF::new(3).unwrap();
+        let boxed = hkdf
+            .derive_salted_secrets(&ikm, &salt, &info, 42)
+            .unwrap();
+        let mut buf = [0u8; 42];
+        hkdf.derive_into(&ikm, &salt, &info, &mut buf).unwrap();
+
+        assert_eq!(&boxed[..], &buf[..]);
+    }
+}